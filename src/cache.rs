@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::SheetFormat;
+
+const CACHE_FILE: &str = "cache.json";
+
+/// Per-sheet, per-format record of what has already been downloaded.
+///
+/// Loaded once at startup and saved periodically (and on shutdown) so that a
+/// crash or Ctrl-C mid-run only costs the in-flight downloads, not the whole
+/// archive.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DownloadCache {
+    #[serde(default)]
+    sheets: HashMap<i32, HashMap<String, CachedEntry>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedEntry {
+    pub content_length: Option<u64>,
+    pub last_modified: Option<String>,
+}
+
+impl DownloadCache {
+    /// Loads `cache.json` from the downloads root, or starts empty if it
+    /// doesn't exist or fails to parse.
+    pub fn load(root: &Path) -> DownloadCache {
+        match std::fs::read_to_string(root.join(CACHE_FILE)) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+            Err(_) => DownloadCache::default()
+        }
+    }
+
+    pub fn save(&self, root: &Path) {
+        if let Ok(contents) = serde_json::to_string_pretty(self) {
+            let _ = std::fs::write(root.join(CACHE_FILE), contents);
+        }
+    }
+
+    /// Whether `sheet_id`/`format` was already downloaded with this exact
+    /// content-length/last-modified pair.
+    pub fn is_up_to_date(&self, sheet_id: i32, format: &SheetFormat, entry: &CachedEntry) -> bool {
+        self.sheets.get(&sheet_id)
+            .and_then(|formats| formats.get(&format.to_string()))
+            .map_or(false, |existing| existing == entry)
+    }
+
+    /// Whether `sheet_id`/`format` has any recorded completion at all,
+    /// regardless of content-length/last-modified.
+    pub fn contains(&self, sheet_id: i32, format: &SheetFormat) -> bool {
+        self.sheets.get(&sheet_id)
+            .map_or(false, |formats| formats.contains_key(&format.to_string()))
+    }
+
+    pub fn mark_downloaded(&mut self, sheet_id: i32, format: &SheetFormat, entry: CachedEntry) {
+        self.sheets.entry(sheet_id).or_default().insert(format.to_string(), entry);
+    }
+}
@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::SheetFormat;
+
+/// Archives sheet music from NinSheetMusic.org.
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+pub struct Args {
+    /// Sheet formats to download.
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [SheetFormat::PDF, SheetFormat::MID, SheetFormat::MUS])]
+    pub formats: Vec<SheetFormat>,
+
+    /// Directory to download the archive into.
+    #[arg(long, default_value = "./downloads/")]
+    pub output_dir: PathBuf,
+
+    /// Number of concurrent download workers.
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(usize).range(1..))]
+    pub threads: usize,
+
+    /// Only archive series whose name matches this regex, or substring if it doesn't compile as one (case-insensitive).
+    #[arg(long)]
+    pub series: Option<String>,
+
+    /// Only archive games whose name matches this regex, or substring if it doesn't compile as one (case-insensitive).
+    #[arg(long)]
+    pub game: Option<String>,
+
+    /// Also emit the manifest as manifest.csv alongside manifest.json.
+    #[arg(long)]
+    pub csv_manifest: bool
+}
@@ -0,0 +1,11 @@
+use regex::RegexBuilder;
+
+/// Tests `haystack` against `pattern`, treating `pattern` as a
+/// case-insensitive regex when it compiles as one, and otherwise falling
+/// back to a plain case-insensitive substring match.
+pub fn matches(pattern: &str, haystack: &str) -> bool {
+    match RegexBuilder::new(pattern).case_insensitive(true).build() {
+        Ok(regex) => regex.is_match(haystack),
+        Err(_) => haystack.to_lowercase().contains(&pattern.to_lowercase())
+    }
+}
@@ -1,37 +1,54 @@
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use async_channel::{Receiver, Sender};
+use clap::Parser;
 use futures::future::join_all;
+use futures::StreamExt;
 use html_escape::decode_html_entities;
+use indicatif::{MultiProgress, ProgressBar};
 use reqwest::Client;
 use sanitize_filename_reader_friendly::sanitize;
-use strum::IntoEnumIterator;
-use strum_macros::{Display, EnumIter};
-use tl::{HTMLTag, Parser};
+use serde::Serialize;
+use strum_macros::Display;
+use tl::{HTMLTag, Parser as HtmlParser};
 use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
 
-#[derive(Display, EnumIter)]
+use cache::{CachedEntry, DownloadCache};
+use cli::Args;
+
+mod cache;
+mod cli;
+mod filter;
+mod manifest;
+mod progress;
+mod retry;
+
+#[derive(Debug, Display, Clone, Copy, clap::ValueEnum)]
+#[value(rename_all = "lowercase")]
 enum SheetFormat {
     PDF,
     MID,
     MUS
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct Serie {
     name: String,
     url: String,
     games: Vec<Game>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct Game {
     name: String,
     system: String,
     sheets: Vec<Sheet>
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 struct Sheet {
     name: String,
     arrangers: Vec<String>,
@@ -58,7 +75,7 @@ impl Serie {
         Ok(())
     }
 
-    fn parse(a_tag: &HTMLTag, parser: &Parser) -> Serie {
+    fn parse(a_tag: &HTMLTag, parser: &HtmlParser) -> Serie {
         let href = a_tag.attributes().get("href").flatten().unwrap().as_utf8_str();
         let name = a_tag.inner_text(parser);
 
@@ -71,7 +88,7 @@ impl Serie {
 }
 
 impl Game {
-    fn parse(section: &HTMLTag, parser: &Parser) -> Game {
+    fn parse(section: &HTMLTag, parser: &HtmlParser) -> Game {
         let heading_text = section.query_selector(parser, "h3").unwrap().next().unwrap();
         let name = heading_text.get(parser).unwrap().inner_text(parser);
         let console_a = section.query_selector(parser, "a[title]").unwrap().next().unwrap();
@@ -89,7 +106,7 @@ impl Game {
 }
 
 impl Sheet {
-    fn parse(li: &HTMLTag, parser: &Parser) -> Sheet {
+    fn parse(li: &HTMLTag, parser: &HtmlParser) -> Sheet {
         let id: i32 = li.attributes().id().unwrap().as_utf8_str()[5..].parse().unwrap();
         let title_element = li.query_selector(parser, "div.tableList-cell--sheetTitle").unwrap().next().unwrap();
         let name = title_element.get(parser).unwrap().inner_text(parser);
@@ -108,14 +125,60 @@ impl Sheet {
         format!("https://www.ninsheetmusic.org/download/{}/{}", format.to_string().to_lowercase(), self.id)
     }
 
-    async fn download(&self, folder_path: &Path, format: SheetFormat, client: &Client) -> Result<(), reqwest::Error> {
-        let path = folder_path.join(format!("{}.{}", sanitize(&self.name), format.to_string().to_lowercase()));
-        let response = client.get(self.get_download_url(format)).send().await?.bytes().await?;
+    fn file_path(&self, folder_path: &Path, format: SheetFormat) -> PathBuf {
+        folder_path.join(format!("{}.{}", sanitize(&self.name), format.to_string().to_lowercase()))
+    }
 
-        let mut file = tokio::fs::File::create(path).await.expect("Couldn't create file.");
-        file.write_all(&response[..]).await.expect("Couldn't write to file.");
+    /// Cheaply checks what's currently served for `format` without fetching
+    /// the body, so the cache can tell "unchanged" from "stale" apart.
+    async fn head(&self, format: SheetFormat, client: &Client) -> Result<CachedEntry, reqwest::Error> {
+        let response = client.head(self.get_download_url(format)).send().await?;
+
+        Ok(CachedEntry {
+            content_length: response.content_length(),
+            last_modified: response.headers().get(reqwest::header::LAST_MODIFIED)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        })
+    }
 
-        Ok(())
+    async fn download(&self, folder_path: &Path, format: SheetFormat, client: &Client, progress: &ProgressBar) -> Result<CachedEntry, reqwest::Error> {
+        let path = self.file_path(folder_path, format);
+
+        let result = retry::with_backoff(|_attempt| async {
+            if path.exists() {
+                let _ = std::fs::remove_file(&path);
+            }
+
+            let response = client.get(self.get_download_url(format)).send().await?;
+
+            let entry = CachedEntry {
+                content_length: response.content_length(),
+                last_modified: response.headers().get(reqwest::header::LAST_MODIFIED)
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from)
+            };
+
+            progress.set_position(0);
+            progress.set_length(entry.content_length.unwrap_or(0));
+
+            let mut file = tokio::fs::File::create(&path).await.expect("Couldn't create file.");
+            let mut stream = response.bytes_stream();
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                file.write_all(&chunk).await.expect("Couldn't write to file.");
+                progress.inc(chunk.len() as u64);
+            }
+
+            Ok(entry)
+        }).await;
+
+        // The last attempt may have failed mid-stream; don't leave a truncated file behind.
+        if result.is_err() && path.exists() {
+            let _ = std::fs::remove_file(&path);
+        }
+
+        result
     }
 }
 
@@ -140,63 +203,177 @@ async fn fetch_series(client: &Client) -> Result<Vec<Serie>, reqwest::Error> {
     Ok(series)
 }
 
-const THREAD_COUNT: i32 = 6;
+/// Number of series indexed concurrently while downloads are already underway.
+const INDEX_CONCURRENCY: usize = 4;
 
 #[tokio::main]
 async fn main() {
     const VERSION: &str = env!("CARGO_PKG_VERSION");
     println!("nsm_archiver v{} by Linkster78\n", VERSION);
 
+    let args = Args::parse();
+
     let client = Client::new();
+    let downloads_root = args.output_dir.as_path();
+    fs::create_dir_all(downloads_root).expect("Couldn't create the downloads folder.");
+
+    let cache = Arc::new(Mutex::new(DownloadCache::load(downloads_root)));
+    let cache_flush = cache.clone();
+    let cache_flush_root = downloads_root.to_owned();
+    let flush_task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(30));
+        loop {
+            interval.tick().await;
+            cache_flush.lock().await.save(&cache_flush_root);
+        }
+    });
+
+    let multi = MultiProgress::new();
+    let overall_bar = multi.add(ProgressBar::new(0));
+    overall_bar.set_style(progress::overall_style());
 
-    println!("> Indexing series...");
+    let _ = multi.println("> Indexing series...");
     let mut series = fetch_series(&client).await.expect("Failed to pull the NinSheetMusic website for series.");
-    println!("< Indexed {} series!", series.len());
-
-    let (tx, rx): (Sender<QueuedDownload>, Receiver<QueuedDownload>) = async_channel::unbounded();
-
-    for serie in series.iter_mut() {
-        println!("> Indexing games for serie {}...", serie.name);
-        serie.populate_games(&client).await.expect("Failed to pull the NinSheetMusic website for games.");
-        println!("< Indexed {} games totalling {} sheets.", serie.games.len(), serie.games.iter().map(|game| game.sheets.len()).sum::<usize>());
-
-        for game in &serie.games {
-            let folder_path_str = format!("./downloads/{}/{}/", sanitize(&serie.name), sanitize(&game.name));
-            let folder_path = Path::new(&folder_path_str);
-            fs::create_dir_all(folder_path).expect("Couldn't create the folder hierarchy.");
-
-            for sheet in &game.sheets {
-                let download = QueuedDownload {
-                    path: folder_path.to_owned(),
-                    sheet: sheet.clone()
-                };
-                let _ = tx.send(download).await;
-            }
-        }
+    if let Some(pattern) = &args.series {
+        series.retain(|serie| filter::matches(pattern, &serie.name));
     }
+    let _ = multi.println(format!("< Indexed {} series, indexing their games and downloading concurrently...", series.len()));
 
-    let mut tasks = vec!();
+    let (tx, rx): (Sender<QueuedDownload>, Receiver<QueuedDownload>) = async_channel::bounded(args.threads * 4);
 
-    for _ in 0..THREAD_COUNT {
+    let mut download_tasks = vec!();
+    for worker_id in 0..args.threads {
         let rx = rx.clone();
+        let cache = cache.clone();
+        let formats = args.formats.clone();
+        let multi = multi.clone();
+        let overall_bar = overall_bar.clone();
+        let worker_bar = multi.add(ProgressBar::new(0));
+        worker_bar.set_style(progress::worker_style());
+        worker_bar.set_prefix(worker_id.to_string());
+
         let task = tokio::spawn(async move {
             let client = Client::new();
+            let mut failures = Vec::new();
 
             while let Ok(queued_dl) = rx.recv().await {
-                for format in SheetFormat::iter() {
-                    queued_dl.sheet.download(&queued_dl.path, format, &client).await?;
+                for format in &formats {
+                    let format = *format;
+                    let file_path = queued_dl.sheet.file_path(&queued_dl.path, format);
+                    let already_cached = cache.lock().await.contains(queued_dl.sheet.id, &format);
+                    if already_cached && file_path.exists() {
+                        if let Ok(current) = queued_dl.sheet.head(format, &client).await {
+                            if cache.lock().await.is_up_to_date(queued_dl.sheet.id, &format, &current) {
+                                continue;
+                            }
+                        }
+                    }
+
+                    worker_bar.set_message(format!("{} ({})", queued_dl.sheet.name, format));
+                    match queued_dl.sheet.download(&queued_dl.path, format, &client, &worker_bar).await {
+                        Ok(entry) => cache.lock().await.mark_downloaded(queued_dl.sheet.id, &format, entry),
+                        Err(err) => {
+                            let _ = multi.println(format!("! Failed to download {} ({}) after {} attempts: {}", queued_dl.sheet.name, format, retry::MAX_DOWNLOAD_ATTEMPTS, err));
+                            failures.push((queued_dl.sheet.name.clone(), format, err));
+                        }
+                    }
                 }
-                println!("+ Downloaded {} in all formats.", queued_dl.sheet.name);
+                overall_bar.inc(1);
+            }
+
+            worker_bar.finish_and_clear();
+            failures
+        });
+        download_tasks.push(task);
+    }
 
-                if rx.is_empty() {
-                    break;
+    let (series_tx, series_rx): (Sender<Serie>, Receiver<Serie>) = async_channel::unbounded();
+    for serie in series {
+        let _ = series_tx.send(serie).await;
+    }
+    let _ = series_tx.close();
+
+    let indexed = Arc::new(Mutex::new(Vec::new()));
+    let mut index_tasks = vec!();
+    for _ in 0..INDEX_CONCURRENCY {
+        let series_rx = series_rx.clone();
+        let tx = tx.clone();
+        let client = client.clone();
+        let game_filter = args.game.clone();
+        let indexed = indexed.clone();
+        let downloads_root = downloads_root.to_owned();
+        let multi = multi.clone();
+        let overall_bar = overall_bar.clone();
+
+        let task = tokio::spawn(async move {
+            while let Ok(mut serie) = series_rx.recv().await {
+                let _ = multi.println(format!("> Indexing games for serie {}...", serie.name));
+                let populated = retry::with_backoff(|_attempt| {
+                    let mut attempt_serie = Serie { name: serie.name.clone(), url: serie.url.clone(), games: Vec::new() };
+                    let client = &client;
+                    async move { attempt_serie.populate_games(client).await.map(|_| attempt_serie.games) }
+                }).await;
+                match populated {
+                    Ok(games) => serie.games = games,
+                    Err(err) => {
+                        let _ = multi.println(format!("! Failed to index serie {}, skipping: {}", serie.name, err));
+                        continue;
+                    }
+                }
+                if let Some(pattern) = &game_filter {
+                    serie.games.retain(|game| filter::matches(pattern, &game.name));
+                }
+                let sheet_count: u64 = serie.games.iter().map(|game| game.sheets.len()).sum::<usize>() as u64;
+                overall_bar.inc_length(sheet_count);
+                let _ = multi.println(format!("< Indexed {} games totalling {} sheets for {}.", serie.games.len(), sheet_count, serie.name));
+
+                for game in &serie.games {
+                    let folder_path = downloads_root.join(sanitize(&serie.name)).join(sanitize(&game.name));
+                    fs::create_dir_all(&folder_path).expect("Couldn't create the folder hierarchy.");
+
+                    for sheet in &game.sheets {
+                        let download = QueuedDownload {
+                            path: folder_path.clone(),
+                            sheet: sheet.clone()
+                        };
+                        let _ = tx.send(download).await;
+                    }
                 }
-            }
 
-            Ok::<_, reqwest::Error>(())
+                indexed.lock().await.push(serie);
+            }
         });
-        tasks.push(task);
+        index_tasks.push(task);
     }
 
-    join_all(tasks).await;
+    drop(tx);
+    join_all(index_tasks).await;
+
+    let series = indexed.lock().await;
+
+    let _ = multi.println("> Writing manifest...");
+    let manifest_entries = manifest::build(&series, &args.formats);
+    manifest::write_json(&manifest_entries, downloads_root);
+    if args.csv_manifest {
+        manifest::write_csv(&manifest_entries, downloads_root);
+    }
+    let _ = multi.println(format!("< Wrote manifest for {} sheets.", manifest_entries.len()));
+
+    let failures: Vec<(String, SheetFormat, reqwest::Error)> = join_all(download_tasks).await.into_iter()
+        .filter_map(Result::ok)
+        .flatten()
+        .collect();
+
+    overall_bar.finish_and_clear();
+    flush_task.abort();
+    cache.lock().await.save(downloads_root);
+
+    if failures.is_empty() {
+        println!("\nAll sheets downloaded successfully.");
+    } else {
+        println!("\n{} download(s) failed after {} attempts:", failures.len(), retry::MAX_DOWNLOAD_ATTEMPTS);
+        for (name, format, err) in &failures {
+            println!("  - {} ({}): {}", name, format, err);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{Serie, SheetFormat};
+
+/// One flattened, self-describing record per sheet, combining its series,
+/// game and per-format download URLs so the archive can be diffed between
+/// runs without re-scraping the site.
+#[derive(Serialize)]
+pub struct ManifestEntry {
+    pub series: String,
+    pub game: String,
+    pub system: String,
+    pub name: String,
+    pub arrangers: Vec<String>,
+    pub id: i32,
+    pub urls: HashMap<String, String>
+}
+
+pub fn build(series: &[Serie], formats: &[SheetFormat]) -> Vec<ManifestEntry> {
+    series.iter()
+        .flat_map(|serie| serie.games.iter().map(move |game| (serie, game)))
+        .flat_map(|(serie, game)| game.sheets.iter().map(move |sheet| (serie, game, sheet)))
+        .map(|(serie, game, sheet)| ManifestEntry {
+            series: serie.name.clone(),
+            game: game.name.clone(),
+            system: game.system.clone(),
+            name: sheet.name.clone(),
+            arrangers: sheet.arrangers.clone(),
+            id: sheet.id,
+            urls: formats.iter()
+                .map(|format| (format.to_string().to_lowercase(), sheet.get_download_url(*format)))
+                .collect()
+        })
+        .collect()
+}
+
+pub fn write_json(entries: &[ManifestEntry], root: &Path) {
+    match serde_json::to_string_pretty(entries) {
+        Ok(contents) => {
+            if let Err(err) = std::fs::write(root.join("manifest.json"), contents) {
+                eprintln!("! Couldn't write manifest.json: {}", err);
+            }
+        },
+        Err(err) => eprintln!("! Couldn't serialize the manifest: {}", err)
+    }
+}
+
+pub fn write_csv(entries: &[ManifestEntry], root: &Path) {
+    let mut writer = match csv::Writer::from_path(root.join("manifest.csv")) {
+        Ok(writer) => writer,
+        Err(err) => {
+            eprintln!("! Couldn't write manifest.csv: {}", err);
+            return;
+        }
+    };
+
+    if let Err(err) = writer.write_record(["series", "game", "system", "name", "arrangers", "id", "urls"]) {
+        eprintln!("! Couldn't write manifest.csv header: {}", err);
+    }
+
+    for entry in entries {
+        let mut urls: Vec<&String> = Vec::new();
+        for format in ["pdf", "mid", "mus"] {
+            if let Some(url) = entry.urls.get(format) {
+                urls.push(url);
+            }
+        }
+
+        let record = [
+            entry.series.as_str(),
+            entry.game.as_str(),
+            entry.system.as_str(),
+            entry.name.as_str(),
+            &entry.arrangers.join("; "),
+            &entry.id.to_string(),
+            &urls.iter().map(|url| url.as_str()).collect::<Vec<_>>().join(" | ")
+        ];
+
+        if let Err(err) = writer.write_record(record) {
+            eprintln!("! Couldn't write manifest.csv record: {}", err);
+        }
+    }
+
+    let _ = writer.flush();
+}
@@ -0,0 +1,15 @@
+use indicatif::ProgressStyle;
+
+/// Style for the single overall "sheets completed / total" bar.
+pub fn overall_style() -> ProgressStyle {
+    ProgressStyle::with_template("{bar:40.cyan/blue} {pos}/{len} sheets ({eta})")
+        .unwrap()
+        .progress_chars("=>-")
+}
+
+/// Style for a per-worker spinner showing the current sheet/format and its
+/// download progress.
+pub fn worker_style() -> ProgressStyle {
+    ProgressStyle::with_template("{spinner:.green} [worker {prefix}] {msg} {bytes}/{total_bytes}")
+        .unwrap()
+}
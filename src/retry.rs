@@ -0,0 +1,27 @@
+use std::future::Future;
+use std::time::Duration;
+
+/// Maximum number of attempts made for a single HTTP request before it's
+/// reported as a failure.
+pub const MAX_DOWNLOAD_ATTEMPTS: u32 = 5;
+
+/// Retries `operation` with exponential backoff (500ms, 1s, 2s, 4s, ...),
+/// giving up once [`MAX_DOWNLOAD_ATTEMPTS`] attempts have failed.
+pub async fn with_backoff<F, Fut, T, E>(mut operation: F) -> Result<T, E>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, E>>
+{
+    let mut attempt = 1;
+    loop {
+        match operation(attempt).await {
+            Ok(value) => return Ok(value),
+            Err(_) if attempt < MAX_DOWNLOAD_ATTEMPTS => {
+                let backoff = Duration::from_millis(500 * 2u64.pow(attempt - 1));
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            },
+            Err(err) => return Err(err)
+        }
+    }
+}